@@ -17,11 +17,16 @@
 // WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
 // CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+use std::fmt;
 use std::marker::PhantomData;
 use stream;
 use super::{Data, WindowUpdate, Ping, GoAway};
 
 
+/// The number of bytes a [`RawHeader`] occupies on the wire.
+pub const HEADER_SIZE: usize = 12;
+
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Type {
     Data,
@@ -39,16 +44,30 @@ pub struct Version(pub u8);
 pub struct Len(pub u32);
 
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub struct Flags(pub u16);
-
-impl Flags {
-    pub fn contains(self, other: Flags) -> bool {
-        self.0 & other.0 == other.0
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct Flags: u16 {
+        const SYN = 1;
+        const ACK = 2;
+        const FIN = 4;
+        const RST = 8;
     }
+}
 
-    pub fn and(self, other: Flags) -> Flags {
-        Flags(self.0 | other.0)
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for (name, _) in self.iter_names() {
+            if !first {
+                write!(f, "|")?
+            }
+            first = false;
+            write!(f, "{}", name)?
+        }
+        if first {
+            write!(f, "-")?
+        }
+        Ok(())
     }
 }
 
@@ -60,10 +79,47 @@ pub const ECODE_PROTO: u32 = 1;
 pub const ECODE_INTERNAL: u32 = 2;
 
 
-pub const SYN: Flags = Flags(1);
-pub const ACK: Flags = Flags(2);
-pub const FIN: Flags = Flags(4);
-pub const RST: Flags = Flags(8);
+/// The reason a peer is terminating the session, carried in a `GoAway` frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GoAwayCode {
+    Normal,
+    ProtocolError,
+    Internal,
+    Other(u32)
+}
+
+impl From<u32> for GoAwayCode {
+    fn from(code: u32) -> Self {
+        match code {
+            CODE_TERM => GoAwayCode::Normal,
+            ECODE_PROTO => GoAwayCode::ProtocolError,
+            ECODE_INTERNAL => GoAwayCode::Internal,
+            other => GoAwayCode::Other(other)
+        }
+    }
+}
+
+impl From<GoAwayCode> for u32 {
+    fn from(code: GoAwayCode) -> Self {
+        match code {
+            GoAwayCode::Normal => CODE_TERM,
+            GoAwayCode::ProtocolError => ECODE_PROTO,
+            GoAwayCode::Internal => ECODE_INTERNAL,
+            GoAwayCode::Other(code) => code
+        }
+    }
+}
+
+impl fmt::Display for GoAwayCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoAwayCode::Normal => write!(f, "normal termination"),
+            GoAwayCode::ProtocolError => write!(f, "protocol error"),
+            GoAwayCode::Internal => write!(f, "internal error"),
+            GoAwayCode::Other(code) => write!(f, "other ({})", code)
+        }
+    }
+}
 
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -75,6 +131,78 @@ pub struct RawHeader {
     pub length: Len
 }
 
+/// An error while decoding a [`RawHeader`] (or a typed [`Header`]) from bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HeaderDecodeError {
+    /// The frame type tag is not one of the known values.
+    Type(u8),
+    /// The flags field contains bits that are not one of `SYN`/`ACK`/`FIN`/`RST`,
+    /// or a `Ping` or `GoAway` header carries `FIN` or `RST`.
+    Flags(u16),
+    /// A `Ping` or `GoAway` header carried a non-zero stream id.
+    StreamId(stream::Id),
+    /// A `try_into_*` conversion was called on a header of a different,
+    /// but perfectly well-formed, type.
+    Mismatch { expected: Type, found: Type }
+}
+
+impl fmt::Display for HeaderDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderDecodeError::Type(t) => write!(f, "unknown frame type: {}", t),
+            HeaderDecodeError::Flags(bits) => write!(f, "invalid flags: {:#x}", bits),
+            HeaderDecodeError::StreamId(id) => write!(f, "unexpected non-zero stream id: {:?}", id),
+            HeaderDecodeError::Mismatch { expected, found } =>
+                write!(f, "expected a {:?} header, found {:?}", expected, found)
+        }
+    }
+}
+
+impl std::error::Error for HeaderDecodeError {}
+
+fn type_tag(typ: Type) -> u8 {
+    match typ {
+        Type::Data => 0,
+        Type::WindowUpdate => 1,
+        Type::Ping => 2,
+        Type::GoAway => 3
+    }
+}
+
+/// Serialise a [`RawHeader`] into its 12-byte, big-endian wire representation.
+pub fn encode(hdr: &RawHeader) -> [u8; HEADER_SIZE] {
+    let mut buf = [0; HEADER_SIZE];
+    buf[0] = hdr.version.0;
+    buf[1] = type_tag(hdr.typ);
+    buf[2 .. 4].copy_from_slice(&hdr.flags.bits().to_be_bytes());
+    buf[4 .. 8].copy_from_slice(&hdr.stream_id.val().to_be_bytes());
+    buf[8 .. 12].copy_from_slice(&hdr.length.0.to_be_bytes());
+    buf
+}
+
+/// Parse a [`RawHeader`] from its 12-byte, big-endian wire representation.
+pub fn decode(buf: &[u8; HEADER_SIZE]) -> Result<RawHeader, HeaderDecodeError> {
+    let typ = match buf[1] {
+        0 => Type::Data,
+        1 => Type::WindowUpdate,
+        2 => Type::Ping,
+        3 => Type::GoAway,
+        t => return Err(HeaderDecodeError::Type(t))
+    };
+    let flags = u16::from_be_bytes([buf[2], buf[3]]);
+    let stream_id = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+    let length = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
+    Ok(RawHeader {
+        version: Version(buf[0]),
+        typ,
+        // Undefined bits are retained here and rejected later by the
+        // `try_into_*` conversions.
+        flags: Flags::from_bits_retain(flags),
+        stream_id: stream::Id::new(stream_id),
+        length: Len(length)
+    })
+}
+
 
 #[derive(Clone, Debug)]
 pub struct Header<T> {
@@ -103,13 +231,85 @@ impl<T> Header<T> {
     }
 }
 
+impl Header<()> {
+    /// Parse a type-erased header from its wire representation.
+    ///
+    /// This only checks that the frame type tag is known; whether the
+    /// header is well-formed for that type is checked by the `try_into_*`
+    /// conversions below.
+    pub fn decode(buf: &[u8; HEADER_SIZE]) -> Result<Header<()>, HeaderDecodeError> {
+        decode(buf).map(Header::assert)
+    }
+
+    fn check_flags(&self) -> Result<(), HeaderDecodeError> {
+        if Flags::from_bits(self.raw_header.flags.bits()).is_none() {
+            return Err(HeaderDecodeError::Flags(self.raw_header.flags.bits()))
+        }
+        Ok(())
+    }
+
+    fn check_zero_stream_id(&self) -> Result<(), HeaderDecodeError> {
+        if self.raw_header.stream_id != stream::Id::new(0) {
+            return Err(HeaderDecodeError::StreamId(self.raw_header.stream_id))
+        }
+        Ok(())
+    }
+
+    fn check_type(&self, expected: Type) -> Result<(), HeaderDecodeError> {
+        if self.raw_header.typ != expected {
+            return Err(HeaderDecodeError::Mismatch { expected, found: self.raw_header.typ })
+        }
+        Ok(())
+    }
+
+    /// Convert into a `Data` header, checking that the type tag matches and
+    /// that no undefined flag bits are set.
+    pub fn try_into_data(self) -> Result<Header<Data>, HeaderDecodeError> {
+        self.check_type(Type::Data)?;
+        self.check_flags()?;
+        Ok(Header::assert(self.raw_header))
+    }
+
+    /// Convert into a `WindowUpdate` header, checking that the type tag
+    /// matches and that no undefined flag bits are set.
+    pub fn try_into_window_update(self) -> Result<Header<WindowUpdate>, HeaderDecodeError> {
+        self.check_type(Type::WindowUpdate)?;
+        self.check_flags()?;
+        Ok(Header::assert(self.raw_header))
+    }
+
+    /// Convert into a `Ping` header, checking that the type tag matches,
+    /// that the stream id is zero, and that only `SYN`/`ACK` are set.
+    pub fn try_into_ping(self) -> Result<Header<Ping>, HeaderDecodeError> {
+        self.check_type(Type::Ping)?;
+        self.check_flags()?;
+        self.check_zero_stream_id()?;
+        if self.raw_header.flags.contains(Flags::FIN) || self.raw_header.flags.contains(Flags::RST) {
+            return Err(HeaderDecodeError::Flags(self.raw_header.flags.bits()))
+        }
+        Ok(Header::assert(self.raw_header))
+    }
+
+    /// Convert into a `GoAway` header, checking that the type tag matches,
+    /// that the stream id is zero, and that only `SYN`/`ACK` are set.
+    pub fn try_into_go_away(self) -> Result<Header<GoAway>, HeaderDecodeError> {
+        self.check_type(Type::GoAway)?;
+        self.check_flags()?;
+        self.check_zero_stream_id()?;
+        if self.raw_header.flags.contains(Flags::FIN) || self.raw_header.flags.contains(Flags::RST) {
+            return Err(HeaderDecodeError::Flags(self.raw_header.flags.bits()))
+        }
+        Ok(Header::assert(self.raw_header))
+    }
+}
+
 impl Header<Data> {
     pub fn data(id: stream::Id, len: u32) -> Self {
         Header {
             raw_header: RawHeader {
                 version: Version(0),
                 typ: Type::Data,
-                flags: Flags(0),
+                flags: Flags::empty(),
                 stream_id: id,
                 length: Len(len)
             },
@@ -118,19 +318,19 @@ impl Header<Data> {
     }
 
     pub fn syn(&mut self) {
-        self.raw_header.flags.0 |= SYN.0
+        self.raw_header.flags |= Flags::SYN
     }
 
     pub fn ack(&mut self) {
-        self.raw_header.flags.0 |= ACK.0
+        self.raw_header.flags |= Flags::ACK
     }
 
     pub fn fin(&mut self) {
-        self.raw_header.flags.0 |= FIN.0
+        self.raw_header.flags |= Flags::FIN
     }
 
     pub fn rst(&mut self) {
-        self.raw_header.flags.0 |= RST.0
+        self.raw_header.flags |= Flags::RST
     }
 
     pub fn len(&self) -> u32 {
@@ -144,7 +344,7 @@ impl Header<WindowUpdate> {
             raw_header: RawHeader {
                 version: Version(0),
                 typ: Type::WindowUpdate,
-                flags: Flags(0),
+                flags: Flags::empty(),
                 stream_id: id,
                 length: Len(credit)
             },
@@ -153,19 +353,19 @@ impl Header<WindowUpdate> {
     }
 
     pub fn syn(&mut self) {
-        self.raw_header.flags.0 |= SYN.0
+        self.raw_header.flags |= Flags::SYN
     }
 
     pub fn ack(&mut self) {
-        self.raw_header.flags.0 |= ACK.0
+        self.raw_header.flags |= Flags::ACK
     }
 
     pub fn fin(&mut self) {
-        self.raw_header.flags.0 |= FIN.0
+        self.raw_header.flags |= Flags::FIN
     }
 
     pub fn rst(&mut self) {
-        self.raw_header.flags.0 |= RST.0
+        self.raw_header.flags |= Flags::RST
     }
 
     pub fn credit(&self) -> u32 {
@@ -179,7 +379,7 @@ impl Header<Ping> {
             raw_header: RawHeader {
                 version: Version(0),
                 typ: Type::Ping,
-                flags: Flags(0),
+                flags: Flags::empty(),
                 stream_id: stream::Id::new(0),
                 length: Len(nonce)
             },
@@ -188,11 +388,11 @@ impl Header<Ping> {
     }
 
     pub fn syn(&mut self) {
-        self.raw_header.flags.0 |= SYN.0
+        self.raw_header.flags |= Flags::SYN
     }
 
     pub fn ack(&mut self) {
-        self.raw_header.flags.0 |= ACK.0
+        self.raw_header.flags |= Flags::ACK
     }
 
     pub fn nonce(&self) -> u32 {
@@ -201,14 +401,14 @@ impl Header<Ping> {
 }
 
 impl Header<GoAway> {
-    pub fn go_away(error_code: u32) -> Self {
+    pub fn go_away(code: GoAwayCode) -> Self {
         Header {
             raw_header: RawHeader {
                 version: Version(0),
                 typ: Type::GoAway,
-                flags: Flags(0),
+                flags: Flags::empty(),
                 stream_id: stream::Id::new(0),
-                length: Len(error_code)
+                length: Len(code.into())
             },
             header_type: PhantomData
         }
@@ -217,5 +417,108 @@ impl Header<GoAway> {
     pub fn error_code(&self) -> u32 {
         self.raw_header.length.0
     }
+
+    pub fn reason(&self) -> GoAwayCode {
+        GoAwayCode::from(self.raw_header.length.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(flags: Flags) -> RawHeader {
+        RawHeader {
+            version: Version(0),
+            typ: Type::Data,
+            flags,
+            stream_id: stream::Id::new(0x1234_5678),
+            length: Len(0x9abc_def0)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let hdr = header(Flags::SYN | Flags::ACK);
+        let bytes = encode(&hdr);
+        assert_eq!(hdr, decode(&bytes).unwrap())
+    }
+
+    #[test]
+    fn wire_layout_is_big_endian() {
+        let hdr = header(Flags::SYN);
+        let bytes = encode(&hdr);
+        assert_eq!(bytes, [0, 0, 0, 1, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0])
+    }
+
+    #[test]
+    fn decode_rejects_unknown_type() {
+        let mut bytes = encode(&header(Flags::empty()));
+        bytes[1] = 0xff;
+        match decode(&bytes) {
+            Err(HeaderDecodeError::Type(0xff)) => {}
+            other => panic!("unexpected result: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn try_into_rejects_unknown_flag_bits() {
+        let raw = header(Flags::from_bits_retain(0x8000));
+        match Header::assert(raw).try_into_data() {
+            Err(HeaderDecodeError::Flags(0x8000)) => {}
+            other => panic!("unexpected result: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn try_into_ping_rejects_non_zero_stream_id() {
+        let mut raw = header(Flags::empty());
+        raw.typ = Type::Ping;
+        match Header::assert(raw).try_into_ping() {
+            Err(HeaderDecodeError::StreamId(id)) => assert_eq!(id, stream::Id::new(0x1234_5678)),
+            other => panic!("unexpected result: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn try_into_ping_rejects_fin() {
+        let mut raw = header(Flags::FIN);
+        raw.typ = Type::Ping;
+        raw.stream_id = stream::Id::new(0);
+        match Header::assert(raw).try_into_ping() {
+            Err(HeaderDecodeError::Flags(bits)) => assert_eq!(bits, Flags::FIN.bits()),
+            other => panic!("unexpected result: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn try_into_go_away_rejects_non_zero_stream_id() {
+        let mut raw = header(Flags::empty());
+        raw.typ = Type::GoAway;
+        match Header::assert(raw).try_into_go_away() {
+            Err(HeaderDecodeError::StreamId(id)) => assert_eq!(id, stream::Id::new(0x1234_5678)),
+            other => panic!("unexpected result: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn try_into_go_away_rejects_rst() {
+        let mut raw = header(Flags::RST);
+        raw.typ = Type::GoAway;
+        raw.stream_id = stream::Id::new(0);
+        match Header::assert(raw).try_into_go_away() {
+            Err(HeaderDecodeError::Flags(bits)) => assert_eq!(bits, Flags::RST.bits()),
+            other => panic!("unexpected result: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn try_into_rejects_type_mismatch() {
+        let raw = header(Flags::empty());
+        match Header::assert(raw).try_into_window_update() {
+            Err(HeaderDecodeError::Mismatch { expected: Type::WindowUpdate, found: Type::Data }) => {}
+            other => panic!("unexpected result: {:?}", other)
+        }
+    }
 }
 